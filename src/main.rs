@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::fs::File;
@@ -7,45 +8,170 @@ use std::process::{Command, Stdio};
 use std::time::Instant;
 
 use anyhow::{bail, Context, Result};
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use tempfile::Builder;
 
 mod unityfs;
+mod vcdiff;
+#[cfg(feature = "mount")]
+mod mount;
 
 use unityfs::{
-    DirectoryEntry, UnityFsBundle, COMP_LZ4, COMP_LZMA, COMP_MASK, COMP_NONE,
+    CompressionOptions, DirectoryEntry, UnityFsBundle, COMP_LZ4, COMP_LZMA, COMP_MASK, COMP_NONE,
 };
 
 #[derive(Parser)]
 #[command(name = "UAEDB", version, about = "Unity asset delta patcher")]
 struct Cli {
-    /// Input Unity asset bundle.
-    input: PathBuf,
-    /// Patch file (.xdelta) for the uncompressed bundle.
-    patch: Option<PathBuf>,
-    /// Output bundle path.
-    output: Option<PathBuf>,
-    /// Write an uncompressed UnityFS bundle to this path and exit.
-    #[arg(long, value_name = "PATH")]
-    uncompress: Option<PathBuf>,
-    /// Patch a specific entry instead of the full uncompressed bundle.
-    #[arg(long)]
-    entry: Option<String>,
-    /// List bundle entries and exit.
-    #[arg(long)]
-    list_entries: bool,
-    /// Working directory for temporary files (default: current dir).
-    #[arg(long)]
-    work_dir: Option<PathBuf>,
-    /// Keep the working directory after completion.
-    #[arg(long)]
-    keep_work: bool,
-    /// Path to xdelta3 binary (default: runtime/xdelta/xdelta3 or xdelta3).
-    #[arg(long)]
-    xdelta: Option<PathBuf>,
-    /// Bundle compression to use when writing output.
-    #[arg(long, value_enum, default_value = "original")]
-    packer: Packer,
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// List bundle entries.
+    List {
+        /// Unity asset bundle to inspect.
+        input: PathBuf,
+    },
+    /// Write an uncompressed UnityFS bundle.
+    Uncompress {
+        /// Input Unity asset bundle.
+        input: PathBuf,
+        /// Output path for the uncompressed bundle.
+        output: PathBuf,
+    },
+    /// Extract a single entry from a bundle.
+    Extract {
+        /// Input Unity asset bundle.
+        input: PathBuf,
+        /// Output path for the extracted entry.
+        output: PathBuf,
+        /// Entry to extract (by path or suffix). Required unless the bundle
+        /// has exactly one entry.
+        #[arg(long)]
+        entry: Option<String>,
+    },
+    /// Apply an xdelta patch, producing a new bundle.
+    Apply {
+        /// Input Unity asset bundle.
+        input: PathBuf,
+        /// Patch file (.xdelta) for the uncompressed bundle.
+        patch: PathBuf,
+        /// Output bundle path.
+        output: PathBuf,
+        /// Patch a specific entry instead of the full uncompressed bundle.
+        #[arg(long)]
+        entry: Option<String>,
+        /// Working directory for temporary files (default: current dir).
+        #[arg(long)]
+        work_dir: Option<PathBuf>,
+        /// Keep the working directory after completion.
+        #[arg(long)]
+        keep_work: bool,
+        /// Path to xdelta3 binary (default: runtime/xdelta/xdelta3 or xdelta3).
+        #[arg(long)]
+        xdelta: Option<PathBuf>,
+        /// Bundle compression to use when writing output.
+        #[arg(long, value_enum, default_value = "original")]
+        packer: Packer,
+        /// Worker threads for block compression (default: available parallelism).
+        #[arg(long)]
+        threads: Option<usize>,
+        /// LZMA preset (0-9, higher = smaller/slower). Only applies with
+        /// `--packer lzma` or `--packer original` on an LZMA bundle.
+        #[arg(long, value_name = "0-9")]
+        lzma_preset: Option<u32>,
+        /// LZMA dictionary size in bytes (e.g. 67108864 for 64 MiB). Must be
+        /// a power of two between 4 KiB and 1 GiB. Wider dictionaries shrink
+        /// large bundles at the cost of encode/decode memory.
+        #[arg(long, value_name = "BYTES")]
+        lzma_dict_size: Option<u32>,
+        /// Decode the patch with the built-in pure-Rust VCDIFF decoder
+        /// instead of shelling out to `xdelta3`, falling back to `xdelta3`
+        /// for any patch feature it doesn't implement. The decoder only
+        /// covers a subset of the format (no combined ADD+COPY instruction
+        /// codes), so most `xdelta3`-generated patches still fall back
+        /// today; this flag is for patches known to stay in that subset.
+        #[arg(long)]
+        in_process: bool,
+    },
+    /// Extract every entry to a directory tree, preserving entry paths.
+    ExtractAll {
+        /// Input Unity asset bundle.
+        input: PathBuf,
+        /// Directory to extract entries into (created if missing).
+        dir: PathBuf,
+    },
+    /// Rebuild a bundle from a directory of extracted (and possibly edited)
+    /// entries, matching files back to entries by relative path.
+    Repack {
+        /// Directory containing extracted entries.
+        dir: PathBuf,
+        /// Original Unity asset bundle, for layout and any entries missing
+        /// from `dir`.
+        input: PathBuf,
+        /// Output bundle path.
+        output: PathBuf,
+        /// Working directory for temporary files (default: current dir).
+        #[arg(long)]
+        work_dir: Option<PathBuf>,
+        /// Keep the working directory after completion.
+        #[arg(long)]
+        keep_work: bool,
+        /// Bundle compression to use when writing output.
+        #[arg(long, value_enum, default_value = "original")]
+        packer: Packer,
+        /// Worker threads for block compression (default: available parallelism).
+        #[arg(long)]
+        threads: Option<usize>,
+        /// LZMA preset (0-9, higher = smaller/slower). Only applies with
+        /// `--packer lzma` or `--packer original` on an LZMA bundle.
+        #[arg(long, value_name = "0-9")]
+        lzma_preset: Option<u32>,
+        /// LZMA dictionary size in bytes (e.g. 67108864 for 64 MiB). Must be
+        /// a power of two between 4 KiB and 1 GiB. Wider dictionaries shrink
+        /// large bundles at the cost of encode/decode memory.
+        #[arg(long, value_name = "BYTES")]
+        lzma_dict_size: Option<u32>,
+        /// Deduplicate identical data blocks instead of recompressing each
+        /// occurrence. Produces a non-standard container that only this
+        /// tool's reader understands.
+        #[arg(long)]
+        dedup: bool,
+    },
+    /// Create an xdelta patch between two bundles.
+    Pack {
+        /// Old (source) Unity asset bundle.
+        old: PathBuf,
+        /// New (target) Unity asset bundle.
+        new: PathBuf,
+        /// Output patch path (.xdelta).
+        patch: PathBuf,
+        /// Diff a specific entry instead of the full uncompressed bundles.
+        #[arg(long)]
+        entry: Option<String>,
+        /// Working directory for temporary files (default: current dir).
+        #[arg(long)]
+        work_dir: Option<PathBuf>,
+        /// Keep the working directory after completion.
+        #[arg(long)]
+        keep_work: bool,
+        /// Path to xdelta3 binary (default: runtime/xdelta/xdelta3 or xdelta3).
+        #[arg(long)]
+        xdelta: Option<PathBuf>,
+    },
+    /// Mount a bundle's entries as a read-only FUSE filesystem.
+    #[cfg(feature = "mount")]
+    Mount {
+        /// Input Unity asset bundle.
+        input: PathBuf,
+        /// Directory to mount the bundle's entries at.
+        mountpoint: PathBuf,
+        /// Working directory for the decompressed bundle data (default: current dir).
+        #[arg(long)]
+        work_dir: Option<PathBuf>,
+    },
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -69,32 +195,94 @@ impl Packer {
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let xdelta = cli.xdelta.unwrap_or_else(default_xdelta_path);
 
-    if let Some(out) = cli.uncompress.as_ref() {
-        return uncompress_only(&cli.input, out);
+    match cli.command {
+        CliCommand::List { input } => list_entries(&input),
+        CliCommand::Uncompress { input, output } => uncompress_only(&input, &output),
+        CliCommand::Extract {
+            input,
+            output,
+            entry,
+        } => extract_entry_path(&input, &output, entry.as_deref()),
+        CliCommand::Apply {
+            input,
+            patch,
+            output,
+            entry,
+            work_dir,
+            keep_work,
+            xdelta,
+            packer,
+            threads,
+            lzma_preset,
+            lzma_dict_size,
+            in_process,
+        } => {
+            let options = compression_options(lzma_preset, lzma_dict_size)?;
+            apply_patch_path(
+                &xdelta.unwrap_or_else(default_xdelta_path),
+                &input,
+                &patch,
+                &output,
+                work_dir.as_ref(),
+                keep_work,
+                entry.as_deref(),
+                packer,
+                threads.unwrap_or_else(default_threads),
+                &options,
+                in_process,
+            )
+        }
+        CliCommand::ExtractAll { input, dir } => extract_all_path(&input, &dir),
+        CliCommand::Repack {
+            dir,
+            input,
+            output,
+            work_dir,
+            keep_work,
+            packer,
+            threads,
+            lzma_preset,
+            lzma_dict_size,
+            dedup,
+        } => {
+            let options = compression_options(lzma_preset, lzma_dict_size)?;
+            repack_bundle_path(
+                &dir,
+                &input,
+                &output,
+                work_dir.as_ref(),
+                keep_work,
+                packer,
+                threads.unwrap_or_else(default_threads),
+                &options,
+                dedup,
+            )
+        }
+        CliCommand::Pack {
+            old,
+            new,
+            patch,
+            entry,
+            work_dir,
+            keep_work,
+            xdelta,
+        } => pack_patch_path(
+            &xdelta.unwrap_or_else(default_xdelta_path),
+            &old,
+            &new,
+            &patch,
+            work_dir.as_ref(),
+            keep_work,
+            entry.as_deref(),
+        ),
+        #[cfg(feature = "mount")]
+        CliCommand::Mount {
+            input,
+            mountpoint,
+            work_dir,
+        } => mount_bundle(&input, &mountpoint, work_dir.as_ref()),
     }
-
-    let patch = cli
-        .patch
-        .as_ref()
-        .context("Missing patch path. Provide PATCH or use --uncompress.")?;
-    let output = cli
-        .output
-        .as_ref()
-        .context("Missing output path. Provide OUTPUT or use --uncompress.")?;
-
-    apply_patch_path(
-        &xdelta,
-        &cli.input,
-        patch,
-        output,
-        cli.work_dir.as_ref(),
-        cli.keep_work,
-        cli.entry.as_deref(),
-        cli.list_entries,
-        cli.packer,
-    )
 }
 
 fn exe_dir() -> Option<PathBuf> {
@@ -123,6 +311,21 @@ fn default_xdelta_path() -> PathBuf {
     PathBuf::from("xdelta3")
 }
 
+fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+fn list_entries(input: &Path) -> Result<()> {
+    if !input.is_file() {
+        bail!("Input bundle not found: {}", input.display());
+    }
+    let bundle = UnityFsBundle::read(input)?;
+    print_entries(bundle.entries());
+    Ok(())
+}
+
 fn uncompress_only(input: &Path, output: &Path) -> Result<()> {
     if !input.is_file() {
         bail!("Input bundle not found: {}", input.display());
@@ -135,6 +338,164 @@ fn uncompress_only(input: &Path, output: &Path) -> Result<()> {
     Ok(())
 }
 
+fn extract_entry_path(input: &Path, output: &Path, entry: Option<&str>) -> Result<()> {
+    if !input.is_file() {
+        bail!("Input bundle not found: {}", input.display());
+    }
+
+    let bundle = UnityFsBundle::read(input)?;
+    let (entry_index, entry_info) = select_entry(bundle.entries(), entry)?;
+    eprintln!(
+        "Selected entry: {} ({} bytes)",
+        entry_info.path, entry_info.size
+    );
+
+    let extract_start = log_step_start("Extracting entry");
+    bundle.extract_entry_random_access(input, entry_index, output)?;
+    log_step_done("Extract", extract_start);
+    Ok(())
+}
+
+fn extract_all_path(input: &Path, dir: &Path) -> Result<()> {
+    if !input.is_file() {
+        bail!("Input bundle not found: {}", input.display());
+    }
+
+    let bundle = UnityFsBundle::read(input)?;
+    fs::create_dir_all(dir).with_context(|| format!("Create dir: {}", dir.display()))?;
+
+    let extract_start = log_step_start("Extracting entries");
+    for (entry_index, entry) in bundle.entries().iter().enumerate() {
+        let out_path = dir.join(normalize_entry_path(&entry.path));
+        bundle.extract_entry_random_access(input, entry_index, &out_path)?;
+    }
+    log_step_done("Extract", extract_start);
+    eprintln!(
+        "Extracted {} entries to {}",
+        bundle.entries().len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+fn repack_bundle_path(
+    dir: &Path,
+    input: &Path,
+    output: &Path,
+    work_dir: Option<&PathBuf>,
+    keep_work: bool,
+    packer: Packer,
+    threads: usize,
+    options: &CompressionOptions,
+    dedup: bool,
+) -> Result<()> {
+    if !input.is_file() {
+        bail!("Input bundle not found: {}", input.display());
+    }
+    if !dir.is_dir() {
+        bail!("Directory not found: {}", dir.display());
+    }
+
+    let bundle = UnityFsBundle::read(input)?;
+
+    let (work_path, _work_guard) = create_work_dir(work_dir, keep_work)?;
+    let data_path = work_path.join("bundle.data");
+    let decompress_start = log_step_start("Uncompressing bundle");
+    bundle.decompress_to_file(input, &data_path)?;
+    log_step_done("Uncompress", decompress_start);
+
+    let entry_by_path: HashMap<String, usize> = bundle
+        .entries()
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (normalize_entry_path(&entry.path), index))
+        .collect();
+
+    let mut replacements = Vec::new();
+    for file_path in collect_dir_files(dir)? {
+        let relative = file_path
+            .strip_prefix(dir)
+            .expect("walked file is under dir")
+            .to_string_lossy()
+            .replace('\\', "/");
+        match entry_by_path.get(&normalize_entry_path(&relative)) {
+            Some(&entry_index) => replacements.push((entry_index, file_path)),
+            None => eprintln!("Warning: {relative} does not match any bundle entry, skipping"),
+        }
+    }
+    if replacements.is_empty() {
+        bail!("No files under {} matched a bundle entry", dir.display());
+    }
+
+    let rebuilt_data_path = work_path.join("bundle_repacked.data");
+    let rebuild_start = log_step_start("Rebuilding bundle");
+    let new_entries = bundle.rebuild_data_file_many(&data_path, &replacements, &rebuilt_data_path)?;
+
+    let (data_flags, block_info_flags) =
+        apply_packer(bundle.flags(), bundle.block_info_flags(), packer);
+
+    bundle.write_bundle_with_dedup(
+        output,
+        &rebuilt_data_path,
+        &new_entries,
+        data_flags,
+        block_info_flags,
+        threads,
+        options,
+        dedup,
+    )?;
+    log_step_done("Rebuild", rebuild_start);
+
+    eprintln!(
+        "Repacked {} of {} entries",
+        replacements.len(),
+        bundle.entries().len()
+    );
+    Ok(())
+}
+
+/// Recursively lists every regular file under `dir`.
+fn collect_dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        for entry in fs::read_dir(&current)
+            .with_context(|| format!("Read dir: {}", current.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                stack.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(feature = "mount")]
+fn mount_bundle(input: &Path, mountpoint: &Path, work_dir: Option<&PathBuf>) -> Result<()> {
+    if !input.is_file() {
+        bail!("Input bundle not found: {}", input.display());
+    }
+    if !mountpoint.is_dir() {
+        bail!("Mountpoint is not a directory: {}", mountpoint.display());
+    }
+
+    let bundle = UnityFsBundle::read(input)?;
+    let (work_path, _work_guard) = create_work_dir(work_dir, false)?;
+    let data_path = work_path.join("bundle.data");
+
+    let decompress_start = log_step_start("Uncompressing bundle");
+    bundle.decompress_to_file(input, &data_path)?;
+    log_step_done("Uncompress", decompress_start);
+
+    eprintln!("Mounted at {} (Ctrl-C or fusermount -u to exit)", mountpoint.display());
+    mount::mount(&data_path, bundle.entries().to_vec(), mountpoint)
+}
+
 fn apply_patch_path(
     xdelta: &Path,
     input: &Path,
@@ -143,8 +504,10 @@ fn apply_patch_path(
     work_dir: Option<&PathBuf>,
     keep_work: bool,
     entry: Option<&str>,
-    list_entries: bool,
     packer: Packer,
+    threads: usize,
+    options: &CompressionOptions,
+    in_process: bool,
 ) -> Result<()> {
     if !input.is_file() {
         bail!("Input bundle not found: {}", input.display());
@@ -152,11 +515,6 @@ fn apply_patch_path(
 
     let bundle = UnityFsBundle::read(input)?;
 
-    if list_entries {
-        print_entries(bundle.entries());
-        return Ok(());
-    }
-
     if !patch_path.is_file() {
         if patch_path.is_dir() {
             bail!(
@@ -167,23 +525,7 @@ fn apply_patch_path(
         bail!("Patch path not found: {}", patch_path.display());
     }
 
-    let work_root = match work_dir {
-        Some(path) => path.clone(),
-        None => std::env::current_dir().context("Get current dir")?,
-    };
-    fs::create_dir_all(&work_root)
-        .with_context(|| format!("Create work root: {}", work_root.display()))?;
-
-    let temp = Builder::new()
-        .prefix("uaedb-work-")
-        .tempdir_in(&work_root)
-        .context("Create temp work dir")?;
-
-    let work_path = if keep_work {
-        temp.keep()
-    } else {
-        temp.path().to_path_buf()
-    };
+    let (work_path, _work_guard) = create_work_dir(work_dir, keep_work)?;
 
     if let Some(entry) = entry {
         let data_path = work_path.join("bundle.data");
@@ -205,7 +547,7 @@ fn apply_patch_path(
 
         let patched_path = work_path.join("entry_patched.bin");
         let patch_start = log_step_start("Applying xdelta patch");
-        run_xdelta(xdelta, &entry_path, patch_path, &patched_path)?;
+        decode_patch(xdelta, &entry_path, patch_path, &patched_path, in_process)?;
         log_step_done("Patch", patch_start);
 
         let rebuilt_data_path = work_path.join("bundle_patched.data");
@@ -223,12 +565,14 @@ fn apply_patch_path(
             packer,
         );
 
-        bundle.write_bundle(
+        bundle.write_bundle_with_options(
             out,
             &rebuilt_data_path,
             &new_entries,
             data_flags,
             block_info_flags,
+            threads,
+            options,
         )?;
         log_step_done("Rebuild", rebuild_start);
     } else {
@@ -242,7 +586,7 @@ fn apply_patch_path(
 
         let patched_bundle_path = work_path.join("bundle_patched.uncompressed");
         let patch_start = log_step_start("Applying xdelta patch");
-        run_xdelta(xdelta, &uncompressed_path, patch_path, &patched_bundle_path)?;
+        decode_patch(xdelta, &uncompressed_path, patch_path, &patched_bundle_path, in_process)?;
         log_step_done("Patch", patch_start);
 
         let patched_bundle =
@@ -303,31 +647,37 @@ fn apply_patch_path(
                 .sum();
             let data_len = data_len.unwrap_or(layout_total);
             if data_len == layout_total {
-                patched_bundle.write_bundle_with_layout(
+                patched_bundle.write_bundle_with_layout_with_options(
                     out,
                     &data_path,
                     patched_bundle.entries(),
                     data_flags,
                     block_info_flags,
                     uncompressed_bundle.blocks(),
+                    threads,
+                    options,
                 )?;
             } else {
-                patched_bundle.write_bundle(
+                patched_bundle.write_bundle_with_options(
                     out,
                     &data_path,
                     patched_bundle.entries(),
                     data_flags,
                     block_info_flags,
+                    threads,
+                    options,
                 )?;
             }
         } else {
-            patched_bundle.write_bundle_with_layout(
+            patched_bundle.write_bundle_with_layout_with_options(
                 out,
                 &data_path,
                 patched_bundle.entries(),
                 data_flags,
                 block_info_flags,
                 patched_bundle.blocks(),
+                threads,
+                options,
             )?;
         }
         log_step_done("Rebuild", rebuild_start);
@@ -340,6 +690,105 @@ fn apply_patch_path(
     Ok(())
 }
 
+/// The inverse of [`apply_patch_path`]: given an `old` and `new` bundle,
+/// uncompresses both (or extracts the named `--entry` from each) and shells
+/// out to `xdelta3 -e` to produce the `.xdelta` patch that `apply` consumes.
+fn pack_patch_path(
+    xdelta: &Path,
+    old: &Path,
+    new: &Path,
+    patch_path: &Path,
+    work_dir: Option<&PathBuf>,
+    keep_work: bool,
+    entry: Option<&str>,
+) -> Result<()> {
+    if !old.is_file() {
+        bail!("Old bundle not found: {}", old.display());
+    }
+    if !new.is_file() {
+        bail!("New bundle not found: {}", new.display());
+    }
+
+    let (work_path, _work_guard) = create_work_dir(work_dir, keep_work)?;
+
+    let (old_path, new_path) = if let Some(entry) = entry {
+        let old_bundle = UnityFsBundle::read(old)?;
+        let new_bundle = UnityFsBundle::read(new)?;
+
+        let (old_index, old_info) = select_entry(old_bundle.entries(), Some(entry))?;
+        eprintln!(
+            "Selected old entry: {} ({} bytes)",
+            old_info.path, old_info.size
+        );
+        let (new_index, new_info) = select_entry(new_bundle.entries(), Some(entry))?;
+        eprintln!(
+            "Selected new entry: {} ({} bytes)",
+            new_info.path, new_info.size
+        );
+
+        let old_entry_path = work_path.join("old_entry.bin");
+        let new_entry_path = work_path.join("new_entry.bin");
+        let extract_start = log_step_start("Extracting entries");
+        old_bundle.extract_entry_random_access(old, old_index, &old_entry_path)?;
+        new_bundle.extract_entry_random_access(new, new_index, &new_entry_path)?;
+        log_step_done("Extract", extract_start);
+
+        (old_entry_path, new_entry_path)
+    } else {
+        let old_bundle = UnityFsBundle::read(old)?;
+        let new_bundle = UnityFsBundle::read(new)?;
+
+        let old_uncompressed_path = work_path.join("old.uncompressed");
+        let new_uncompressed_path = work_path.join("new.uncompressed");
+        let unpack_start = log_step_start("Uncompressing bundles");
+        old_bundle.unpack_to_file(old, &old_uncompressed_path)?;
+        new_bundle.unpack_to_file(new, &new_uncompressed_path)?;
+        log_step_done("Uncompress", unpack_start);
+
+        (old_uncompressed_path, new_uncompressed_path)
+    };
+
+    let pack_start = log_step_start("Creating xdelta patch");
+    run_xdelta_encode(xdelta, &old_path, &new_path, patch_path)?;
+    log_step_done("Pack", pack_start);
+
+    if keep_work {
+        eprintln!("Work directory kept at: {}", work_path.display());
+    }
+
+    Ok(())
+}
+
+/// Creates a temp work directory under `work_dir` (or the current dir).
+/// Returns the path alongside the `TempDir` guard that owns it: the caller
+/// must hold onto the guard for as long as it uses the path, since dropping
+/// it removes the directory. When `keep_work` is set, the guard is consumed
+/// up front (via `TempDir::keep`) so the directory outlives the guard and
+/// the returned guard is `None`.
+fn create_work_dir(
+    work_dir: Option<&PathBuf>,
+    keep_work: bool,
+) -> Result<(PathBuf, Option<tempfile::TempDir>)> {
+    let work_root = match work_dir {
+        Some(path) => path.clone(),
+        None => std::env::current_dir().context("Get current dir")?,
+    };
+    fs::create_dir_all(&work_root)
+        .with_context(|| format!("Create work root: {}", work_root.display()))?;
+
+    let temp = Builder::new()
+        .prefix("uaedb-work-")
+        .tempdir_in(&work_root)
+        .context("Create temp work dir")?;
+
+    if keep_work {
+        Ok((temp.keep(), None))
+    } else {
+        let path = temp.path().to_path_buf();
+        Ok((path, Some(temp)))
+    }
+}
+
 fn apply_packer(flags: u32, block_info_flags: u16, packer: Packer) -> (u32, u16) {
     let Some(compression) = packer.override_compression() else {
         return (flags, block_info_flags);
@@ -351,6 +800,39 @@ fn apply_packer(flags: u32, block_info_flags: u16, packer: Packer) -> (u32, u16)
     (new_flags, new_block_info_flags)
 }
 
+/// Builds [`CompressionOptions`] from `--lzma-preset`/`--lzma-dict-size`,
+/// leaving the documented defaults in place when a flag is absent so output
+/// without these flags stays bit-identical to before they existed.
+fn compression_options(
+    lzma_preset: Option<u32>,
+    lzma_dict_size: Option<u32>,
+) -> Result<CompressionOptions> {
+    let mut options = CompressionOptions::default();
+
+    if let Some(preset) = lzma_preset {
+        if preset > 9 {
+            bail!("--lzma-preset must be between 0 and 9, got {}", preset);
+        }
+        options.lzma_preset = preset;
+    }
+
+    if let Some(dict_size) = lzma_dict_size {
+        const MIN_DICT_SIZE: u32 = 1 << 12;
+        const MAX_DICT_SIZE: u32 = 1 << 30;
+        if dict_size < MIN_DICT_SIZE || dict_size > MAX_DICT_SIZE || !dict_size.is_power_of_two() {
+            bail!(
+                "--lzma-dict-size must be a power of two between {} and {} bytes, got {}",
+                MIN_DICT_SIZE,
+                MAX_DICT_SIZE,
+                dict_size
+            );
+        }
+        options.lzma_dict_size = dict_size;
+    }
+
+    Ok(options)
+}
+
 fn extract_raw_data(input_path: &Path, start: u64, len: u64, output_path: &Path) -> Result<()> {
     if let Some(parent) = output_path.parent() {
         fs::create_dir_all(parent)
@@ -399,7 +881,7 @@ fn select_entry<'a>(
         }
         if exact_matches.len() > 1 {
             bail!(
-                "Entry matches multiple files: {} ({} matches). Use --list-entries.",
+                "Entry matches multiple files: {} ({} matches). Use the list command.",
                 entry,
                 exact_matches.len()
             );
@@ -415,13 +897,13 @@ fn select_entry<'a>(
         }
         if suffix_matches.len() > 1 {
             bail!(
-                "Entry matches multiple files by suffix: {} ({} matches). Use --list-entries.",
+                "Entry matches multiple files by suffix: {} ({} matches). Use the list command.",
                 entry,
                 suffix_matches.len()
             );
         }
 
-        bail!("Entry not found: {}. Use --list-entries.", entry);
+        bail!("Entry not found: {}. Use the list command.", entry);
     }
 
     if entries.len() == 1 {
@@ -435,7 +917,7 @@ fn select_entry<'a>(
         .collect::<Vec<_>>()
         .join(", ");
     bail!(
-        "Expected exactly 1 bundle entry, found {}. Use --entry or --list-entries. Entries: {}",
+        "Expected exactly 1 bundle entry, found {}. Use --entry or the list command. Entries: {}",
         entries.len(),
         preview
     );
@@ -447,7 +929,31 @@ fn print_entries(entries: &[DirectoryEntry]) {
     }
 }
 
-fn run_xdelta(xdelta: &Path, source: &Path, patch: &Path, output: &Path) -> Result<()> {
+/// Decodes `patch` against `source`, trying the in-process VCDIFF decoder
+/// first when `in_process` is set. Falls back to the external `xdelta3`
+/// binary whenever the decoder bails (secondary compression, a custom code
+/// table, or an instruction code outside the subset it implements — notably
+/// the combined ADD+COPY codes `xdelta3` emits by default, so most patches
+/// take this fallback path rather than the in-process one).
+fn decode_patch(
+    xdelta: &Path,
+    source: &Path,
+    patch: &Path,
+    output: &Path,
+    in_process: bool,
+) -> Result<()> {
+    if in_process {
+        match vcdiff::decode_file(source, patch, output) {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                eprintln!("In-process VCDIFF decode failed ({err:#}), falling back to xdelta3");
+            }
+        }
+    }
+    run_xdelta_decode(xdelta, source, patch, output)
+}
+
+fn run_xdelta_decode(xdelta: &Path, source: &Path, patch: &Path, output: &Path) -> Result<()> {
     if output.exists() {
         fs::remove_file(output)
             .with_context(|| format!("Remove existing file: {}", output.display()))?;
@@ -472,6 +978,34 @@ fn run_xdelta(xdelta: &Path, source: &Path, patch: &Path, output: &Path) -> Resu
     Ok(())
 }
 
+/// Inverse of [`run_xdelta_decode`]: shells out to `xdelta3 -e` to encode
+/// `new` against `old` as a patch, instead of decoding a patch against a
+/// source.
+fn run_xdelta_encode(xdelta: &Path, old: &Path, new: &Path, patch: &Path) -> Result<()> {
+    if patch.exists() {
+        fs::remove_file(patch)
+            .with_context(|| format!("Remove existing file: {}", patch.display()))?;
+    }
+
+    let status = Command::new(xdelta)
+        .arg("-e")
+        .arg("-s")
+        .arg(old)
+        .arg(new)
+        .arg(patch)
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .with_context(|| format!("Run xdelta3 encoding {} -> {}", old.display(), new.display()))?;
+
+    if !status.success() {
+        bail!("xdelta failed (exit {}). See output above.", status);
+    }
+
+    Ok(())
+}
+
 fn log_step_start(label: &str) -> Instant {
     eprintln!("{label}...");
     Instant::now()
@@ -0,0 +1,264 @@
+//! Read-only FUSE view of a bundle's [`DirectoryEntry`] list.
+//!
+//! Requires the `mount` feature (pulls in `fuser`, Linux/macOS only via
+//! libfuse). The bundle is decompressed once into `bundle.data` under the
+//! work directory; every file read is served as a byte-range slice of that
+//! file at `entry.offset .. entry.offset + entry.size`, so browsing assets
+//! never materializes the whole uncompressed bundle as separate files.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    Request,
+};
+
+use crate::unityfs::DirectoryEntry;
+
+const TTL: Duration = Duration::from_secs(60);
+const ROOT_INO: u64 = 1;
+
+enum Node {
+    Dir {
+        name: String,
+        parent: u64,
+        children: Vec<u64>,
+    },
+    File {
+        name: String,
+        entry_index: usize,
+    },
+}
+
+impl Node {
+    fn name(&self) -> &str {
+        match self {
+            Node::Dir { name, .. } => name,
+            Node::File { name, .. } => name,
+        }
+    }
+}
+
+/// A read-only FUSE filesystem exposing `entries` as files, with directory
+/// structure taken from splitting each entry's path on `/`.
+pub struct BundleFs {
+    data_file: File,
+    entries: Vec<DirectoryEntry>,
+    nodes: HashMap<u64, Node>,
+    next_ino: u64,
+}
+
+impl BundleFs {
+    pub fn new(data_path: &Path, entries: Vec<DirectoryEntry>) -> Result<Self> {
+        let data_file = File::open(data_path)
+            .with_context(|| format!("Open bundle data: {}", data_path.display()))?;
+
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            ROOT_INO,
+            Node::Dir {
+                name: String::new(),
+                parent: ROOT_INO,
+                children: Vec::new(),
+            },
+        );
+
+        let mut fs = Self {
+            data_file,
+            entries: Vec::new(),
+            nodes,
+            next_ino: ROOT_INO + 1,
+        };
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            fs.insert_entry(index, &entry);
+            fs.entries.push(entry);
+        }
+
+        Ok(fs)
+    }
+
+    fn insert_entry(&mut self, entry_index: usize, entry: &DirectoryEntry) {
+        let components: Vec<String> = entry
+            .path
+            .replace('\\', "/")
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let mut parent_ino = ROOT_INO;
+        for (depth, component) in components.iter().enumerate() {
+            let is_last = depth == components.len() - 1;
+            if let Some(existing) = self.child_named(parent_ino, component) {
+                parent_ino = existing;
+                continue;
+            }
+
+            let ino = self.next_ino;
+            self.next_ino += 1;
+
+            if is_last {
+                self.nodes.insert(
+                    ino,
+                    Node::File {
+                        name: component.to_string(),
+                        entry_index,
+                    },
+                );
+            } else {
+                self.nodes.insert(
+                    ino,
+                    Node::Dir {
+                        name: component.to_string(),
+                        parent: parent_ino,
+                        children: Vec::new(),
+                    },
+                );
+            }
+
+            if let Some(Node::Dir { children, .. }) = self.nodes.get_mut(&parent_ino) {
+                children.push(ino);
+            }
+            parent_ino = ino;
+        }
+    }
+
+    fn child_named(&self, parent_ino: u64, name: &str) -> Option<u64> {
+        let Node::Dir { children, .. } = self.nodes.get(&parent_ino)? else {
+            return None;
+        };
+        children
+            .iter()
+            .copied()
+            .find(|child| self.nodes.get(child).map(Node::name) == Some(name))
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let node = self.nodes.get(&ino)?;
+        let now = SystemTime::now();
+        let (kind, size) = match node {
+            Node::Dir { .. } => (FileType::Directory, 0),
+            Node::File { entry_index, .. } => (FileType::RegularFile, self.entries[*entry_index].size),
+        };
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind,
+            perm: if kind == FileType::Directory { 0o555 } else { 0o444 },
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+}
+
+impl Filesystem for BundleFs {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let Some(name) = name.to_str() else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+        match self.child_named(parent, name) {
+            Some(ino) => reply.entry(&TTL, &self.attr(ino).expect("node just looked up"), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(Node::File { entry_index, .. }) = self.nodes.get(&ino) else {
+            reply.error(libc::ENOENT);
+            return;
+        };
+        let entry = &self.entries[*entry_index];
+        let offset = offset as u64;
+        if offset >= entry.size {
+            reply.data(&[]);
+            return;
+        }
+        let read_len = std::cmp::min(size as u64, entry.size - offset) as usize;
+        let mut buf = vec![0u8; read_len];
+        if self
+            .data_file
+            .seek(SeekFrom::Start(entry.offset + offset))
+            .and_then(|_| self.data_file.read_exact(&mut buf))
+            .is_err()
+        {
+            reply.error(libc::EIO);
+            return;
+        }
+        reply.data(&buf);
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(Node::Dir { children, parent, .. }) = self.nodes.get(&ino) else {
+            reply.error(libc::ENOTDIR);
+            return;
+        };
+        let mut listing = vec![(ino, FileType::Directory, ".".to_string())];
+        listing.push((*parent, FileType::Directory, "..".to_string()));
+        for &child in children {
+            let node = &self.nodes[&child];
+            let kind = match node {
+                Node::Dir { .. } => FileType::Directory,
+                Node::File { .. } => FileType::RegularFile,
+            };
+            listing.push((child, kind, node.name().to_string()));
+        }
+
+        for (index, (child_ino, kind, name)) in listing.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(child_ino, (index + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mounts `entries`, served from the decompressed bundle data at
+/// `data_path`, at `mountpoint`, blocking until the filesystem
+/// is unmounted (e.g. `fusermount -u mountpoint`, or Ctrl-C).
+pub fn mount(data_path: &Path, entries: Vec<DirectoryEntry>, mountpoint: &Path) -> Result<()> {
+    let fs = BundleFs::new(data_path, entries)?;
+    let options = vec![MountOption::RO, MountOption::FSName("uaedb".to_string())];
+    fuser::mount2(fs, mountpoint, &options)
+        .with_context(|| format!("Mount FUSE filesystem at {}", mountpoint.display()))
+}